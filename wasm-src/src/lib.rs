@@ -15,6 +15,11 @@ fn panic(_info: &PanicInfo) -> ! {
 static mut BUFFER: [u8; 4 * 1024 * 1024] = [0; 4 * 1024 * 1024]; // 4MB buffer
 static mut FLOAT_BUFFER: [f32; 1024 * 1024] = [0.0; 1024 * 1024]; // 1M floats
 
+// Scratch space for median-cut palette generation: pixel indices into
+// BUFFER, reordered in place as boxes are split. Sized to match
+// FLOAT_BUFFER's pixel capacity.
+static mut INDEX_BUFFER: [u32; 1024 * 1024] = [0; 1024 * 1024];
+
 #[no_mangle]
 pub extern "C" fn get_buffer_ptr() -> *mut u8 {
     unsafe { BUFFER.as_mut_ptr() }
@@ -42,6 +47,49 @@ pub extern "C" fn calc_brightness_batch(pixel_count: usize) {
     }
 }
 
+// sRGB -> linear lookup table used by calc_brightness_batch_linear.
+// `lut[v] = if s <= 0.04045 { s/12.92 } else { ((s+0.055)/1.055).powf(2.4) }`
+// with `s = v/255.0`. Computing `powf` has no cheap no_std implementation, so
+// the table is supplied by the JS side (which has Math.pow) via set_gamma_lut
+// rather than computed in WASM.
+static mut GAMMA_LUT: [f32; 256] = [0.0; 256];
+static mut GAMMA_LUT_READY: bool = false;
+
+/// Load the sRGB-to-linear gamma lookup table from 256 consecutive f32
+/// values in FLOAT_BUFFER starting at `offset`. Must be called once before
+/// calc_brightness_batch_linear.
+#[no_mangle]
+pub extern "C" fn set_gamma_lut(offset: usize) {
+    unsafe {
+        for i in 0..256 {
+            GAMMA_LUT[i] = FLOAT_BUFFER[offset + i];
+        }
+        GAMMA_LUT_READY = true;
+    }
+}
+
+/// Calculate brightness for all pixels, linearizing each channel through the
+/// sRGB gamma LUT before combining with BT.601 luma weights. This avoids the
+/// midtone over-darkening that comes from applying luma directly to
+/// gamma-encoded bytes.
+/// Input: RGBA pixels in BUFFER
+/// Output: brightness values (0-1) in FLOAT_BUFFER
+#[no_mangle]
+pub extern "C" fn calc_brightness_batch_linear(pixel_count: usize) {
+    unsafe {
+        if !GAMMA_LUT_READY {
+            return;
+        }
+        for i in 0..pixel_count {
+            let pi = i * 4;
+            let r = GAMMA_LUT[BUFFER[pi] as usize];
+            let g = GAMMA_LUT[BUFFER[pi + 1] as usize];
+            let b = GAMMA_LUT[BUFFER[pi + 2] as usize];
+            FLOAT_BUFFER[i] = 0.299 * r + 0.587 * g + 0.114 * b;
+        }
+    }
+}
+
 /// Apply contrast adjustment to brightness values in FLOAT_BUFFER
 #[no_mangle]
 pub extern "C" fn apply_contrast(pixel_count: usize, contrast: f32) {
@@ -100,6 +148,68 @@ pub extern "C" fn apply_histogram_eq(pixel_count: usize) {
     }
 }
 
+/// Apply percentile-based auto-levels (black/white point stretching) to
+/// brightness values in FLOAT_BUFFER. Builds the same 256-bin histogram as
+/// apply_histogram_eq, walks its CDF to find the brightness values at the
+/// `low_pct` and `high_pct` percentiles, then linearly remaps every value so
+/// the low point maps to 0 and the high point to 1, clamping outliers. This
+/// is a gentler contrast stretch than full equalization since it ignores a
+/// few stray bright or dark pixels.
+#[no_mangle]
+pub extern "C" fn apply_auto_levels(pixel_count: usize, low_pct: f32, high_pct: f32) {
+    unsafe {
+        // Build histogram (256 bins)
+        let mut histogram = [0u32; 256];
+        for i in 0..pixel_count {
+            let bin = (FLOAT_BUFFER[i] * 255.0) as usize;
+            let bin = if bin > 255 { 255 } else { bin };
+            histogram[bin] += 1;
+        }
+
+        // Build CDF
+        let mut cdf = [0u32; 256];
+        cdf[0] = histogram[0];
+        for i in 1..256 {
+            cdf[i] = cdf[i - 1] + histogram[i];
+        }
+
+        let total = pixel_count as f32;
+        let low_count = (low_pct / 100.0 * total) as u32;
+        let high_count = (high_pct / 100.0 * total) as u32;
+
+        // Low point: first bin whose CDF exceeds the low target count.
+        // High point: first bin whose CDF reaches the high target count.
+        let mut low_bin = 0usize;
+        let mut high_bin = 255usize;
+        for i in 0..256 {
+            if cdf[i] > low_count {
+                low_bin = i;
+                break;
+            }
+        }
+        for i in 0..256 {
+            if cdf[i] >= high_count {
+                high_bin = i;
+                break;
+            }
+        }
+
+        let low = low_bin as f32 / 255.0;
+        let high = high_bin as f32 / 255.0;
+        let range = high - low;
+        if range <= 0.0 {
+            return;
+        }
+
+        for i in 0..pixel_count {
+            let mut v = (FLOAT_BUFFER[i] - low) / range;
+            if v < 0.0 { v = 0.0; }
+            if v > 1.0 { v = 1.0; }
+            FLOAT_BUFFER[i] = v;
+        }
+    }
+}
+
 /// Find nearest color in palette for each pixel
 /// Input: RGBA pixels in BUFFER, palette colors after pixels
 /// Output: Overwrites RGB in BUFFER with nearest palette colors
@@ -149,6 +259,206 @@ pub extern "C" fn nearest_color_batch(
     }
 }
 
+// Perceptual-weighting lookup table used by nearest_color_batch_perceptual.
+// `lut[v] = (v/255.0)^(1/0.57)`, the libimagequant channel warp that brings
+// RGB distance closer to how the eye weights it. Computed on the JS side and
+// loaded via set_perceptual_lut for the same no_std/powf reason as
+// GAMMA_LUT (see set_gamma_lut).
+static mut PERCEPTUAL_LUT: [f32; 256] = [0.0; 256];
+static mut PERCEPTUAL_LUT_READY: bool = false;
+
+/// Load the perceptual channel-warp lookup table from 256 consecutive f32
+/// values in FLOAT_BUFFER starting at `offset`. Must be called once before
+/// nearest_color_batch_perceptual.
+#[no_mangle]
+pub extern "C" fn set_perceptual_lut(offset: usize) {
+    unsafe {
+        for i in 0..256 {
+            PERCEPTUAL_LUT[i] = FLOAT_BUFFER[offset + i];
+        }
+        PERCEPTUAL_LUT_READY = true;
+    }
+}
+
+/// Find nearest color in palette for each pixel using libimagequant's
+/// perceptual distance metric instead of plain squared RGB Euclidean
+/// distance: channels are warped through PERCEPTUAL_LUT and weighted
+/// 0.5/1.0/0.45 for R/G/B. Palette entries are RGB-only (see
+/// generate_palette), so there is no per-candidate alpha to match against;
+/// alpha is not considered here.
+/// Input: RGBA pixels in BUFFER, palette colors after pixels
+/// Output: Overwrites RGB in BUFFER with nearest palette colors
+/// palette_offset: where palette starts in BUFFER (after pixel data)
+/// palette_size: number of colors in palette
+#[no_mangle]
+pub extern "C" fn nearest_color_batch_perceptual(
+    pixel_count: usize,
+    palette_offset: usize,
+    palette_size: usize
+) {
+    unsafe {
+        if !PERCEPTUAL_LUT_READY {
+            return;
+        }
+        for i in 0..pixel_count {
+            let pi = i * 4;
+            let r = BUFFER[pi];
+            let g = BUFFER[pi + 1];
+            let b = BUFFER[pi + 2];
+
+            let pr_w = PERCEPTUAL_LUT[r as usize];
+            let pg_w = PERCEPTUAL_LUT[g as usize];
+            let pb_w = PERCEPTUAL_LUT[b as usize];
+
+            let mut min_dist = f32::MAX;
+            let mut best_r = r;
+            let mut best_g = g;
+            let mut best_b = b;
+
+            for j in 0..palette_size {
+                let pj = palette_offset + j * 3;
+                let cr = BUFFER[pj];
+                let cg = BUFFER[pj + 1];
+                let cb = BUFFER[pj + 2];
+
+                let dr = pr_w - PERCEPTUAL_LUT[cr as usize];
+                let dg = pg_w - PERCEPTUAL_LUT[cg as usize];
+                let db = pb_w - PERCEPTUAL_LUT[cb as usize];
+
+                let dist = 0.5 * dr * dr + 1.0 * dg * dg + 0.45 * db * db;
+
+                if dist < min_dist {
+                    min_dist = dist;
+                    best_r = cr;
+                    best_g = cg;
+                    best_b = cb;
+                }
+            }
+
+            BUFFER[pi] = best_r;
+            BUFFER[pi + 1] = best_g;
+            BUFFER[pi + 2] = best_b;
+        }
+    }
+}
+
+// generate_palette never needs more boxes than this; it also bounds
+// INDEX_BUFFER's box-tracking array so both stay fixed-size (no allocator
+// in this #![no_std] module).
+const MAX_PALETTE_COLORS: usize = 256;
+
+/// Generate an adaptive color palette from the source image using median-cut
+/// quantization: repeatedly split the box with the largest single-channel
+/// spread at the median along that axis until `max_colors` boxes exist or no
+/// box can be split further.
+/// Input: RGBA pixels in BUFFER
+/// Output: one averaged RGB triple per box, written to BUFFER at
+/// `palette_offset`
+/// Returns: number of colors actually produced (<= max_colors)
+#[no_mangle]
+pub extern "C" fn generate_palette(pixel_count: usize, max_colors: usize, palette_offset: usize) -> usize {
+    let max_colors = if max_colors > MAX_PALETTE_COLORS { MAX_PALETTE_COLORS } else { max_colors };
+    if pixel_count == 0 || max_colors == 0 {
+        return 0;
+    }
+
+    unsafe {
+        for i in 0..pixel_count {
+            INDEX_BUFFER[i] = i as u32;
+        }
+
+        // Box ranges into INDEX_BUFFER: (start, end), end exclusive.
+        let mut boxes: [(usize, usize); MAX_PALETTE_COLORS] = [(0, 0); MAX_PALETTE_COLORS];
+        boxes[0] = (0, pixel_count);
+        let mut box_count = 1usize;
+
+        while box_count < max_colors {
+            let mut split_idx = usize::MAX;
+            let mut split_axis = 0usize;
+            let mut best_spread = -1i32;
+
+            for bi in 0..box_count {
+                let (start, end) = boxes[bi];
+                if end - start < 2 {
+                    continue;
+                }
+                let (axis, spread) = channel_spread(start, end);
+                if spread > best_spread {
+                    best_spread = spread;
+                    split_idx = bi;
+                    split_axis = axis;
+                }
+            }
+
+            if split_idx == usize::MAX || best_spread <= 0 {
+                break;
+            }
+
+            let (start, end) = boxes[split_idx];
+            INDEX_BUFFER[start..end]
+                .sort_unstable_by_key(|&idx| channel_of(idx as usize, split_axis));
+
+            let mid = start + (end - start) / 2;
+            boxes[split_idx] = (start, mid);
+            boxes[box_count] = (mid, end);
+            box_count += 1;
+        }
+
+        for bi in 0..box_count {
+            let (start, end) = boxes[bi];
+            let count = (end - start) as u32;
+            let mut sum_r = 0u32;
+            let mut sum_g = 0u32;
+            let mut sum_b = 0u32;
+            for k in start..end {
+                let pi = INDEX_BUFFER[k] as usize * 4;
+                sum_r += BUFFER[pi] as u32;
+                sum_g += BUFFER[pi + 1] as u32;
+                sum_b += BUFFER[pi + 2] as u32;
+            }
+
+            let po = palette_offset + bi * 3;
+            BUFFER[po] = (sum_r / count) as u8;
+            BUFFER[po + 1] = (sum_g / count) as u8;
+            BUFFER[po + 2] = (sum_b / count) as u8;
+        }
+
+        box_count
+    }
+}
+
+/// Read the R (axis 0), G (axis 1) or B (axis 2) channel of the pixel at
+/// `pixel_index` in BUFFER.
+#[inline]
+unsafe fn channel_of(pixel_index: usize, axis: usize) -> u8 {
+    BUFFER[pixel_index * 4 + axis]
+}
+
+/// Find the channel (R/G/B) with the largest min/max spread across the
+/// pixels referenced by INDEX_BUFFER[start..end], returning (axis, spread).
+#[inline]
+unsafe fn channel_spread(start: usize, end: usize) -> (usize, i32) {
+    let mut best_axis = 0usize;
+    let mut best_spread = -1i32;
+
+    for axis in 0..3 {
+        let mut lo = 255i32;
+        let mut hi = 0i32;
+        for k in start..end {
+            let v = channel_of(INDEX_BUFFER[k] as usize, axis) as i32;
+            if v < lo { lo = v; }
+            if v > hi { hi = v; }
+        }
+        let spread = hi - lo;
+        if spread > best_spread {
+            best_spread = spread;
+            best_axis = axis;
+        }
+    }
+
+    (best_axis, best_spread)
+}
+
 /// Apply saturation adjustment to pixels in BUFFER
 #[no_mangle]
 pub extern "C" fn apply_saturation(pixel_count: usize, saturation: f32) {
@@ -183,6 +493,255 @@ fn clamp_u8(v: f32) -> u8 {
     else { v as u8 }
 }
 
+/// Cube root of a non-negative-or-negative f32 via a bit-hack initial guess
+/// refined by Newton-Raphson. core has no cbrt (this is #![no_std]), and
+/// Oklab's LMS step needs it for arbitrary values, not just the 256 fixed
+/// byte levels GAMMA_LUT covers.
+fn cbrtf(x: f32) -> f32 {
+    if x == 0.0 {
+        return 0.0;
+    }
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+
+    let guess_bits = x.to_bits() / 3 + 0x2a514067;
+    let mut y = f32::from_bits(guess_bits);
+    for _ in 0..5 {
+        y = (2.0 * y + x / (y * y)) / 3.0;
+    }
+
+    sign * y
+}
+
+/// Re-encode a linear-light value (0..1) back to an sRGB byte by binary
+/// search over GAMMA_LUT, which holds the monotonic sRGB-to-linear curve.
+/// This avoids needing a `powf(_, 1/2.4)` gamma-encode, which #![no_std]
+/// has no built-in for either.
+unsafe fn srgb_encode(linear: f32) -> u8 {
+    let mut lo = 0usize;
+    let mut hi = 255usize;
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if GAMMA_LUT[mid] < linear {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    if lo > 0 && (linear - GAMMA_LUT[lo - 1]).abs() <= (GAMMA_LUT[lo] - linear).abs() {
+        (lo - 1) as u8
+    } else {
+        lo as u8
+    }
+}
+
+/// Apply an Oklab-backed saturation adjustment to pixels in BUFFER. Unlike
+/// apply_saturation, which mixes toward a BT.601 gray in gamma-encoded sRGB,
+/// this linearizes sRGB through GAMMA_LUT (see set_gamma_lut, which must be
+/// called first), converts to Oklab, scales only the `a`/`b` chroma
+/// components by `saturation` while leaving `L` fixed, then converts back.
+/// Separating chroma from lightness means saturation changes don't darken or
+/// hue-shift the result. When `write_brightness` is set, the Oklab `L`
+/// channel is also written to FLOAT_BUFFER as a perceptual brightness source
+/// for glyph selection.
+#[no_mangle]
+pub extern "C" fn apply_saturation_oklab(pixel_count: usize, saturation: f32, write_brightness: bool) {
+    if saturation == 1.0 && !write_brightness {
+        return;
+    }
+    unsafe {
+        if !GAMMA_LUT_READY {
+            return;
+        }
+        for i in 0..pixel_count {
+            let pi = i * 4;
+            let r = GAMMA_LUT[BUFFER[pi] as usize];
+            let g = GAMMA_LUT[BUFFER[pi + 1] as usize];
+            let b = GAMMA_LUT[BUFFER[pi + 2] as usize];
+
+            let l = 0.4122 * r + 0.5363 * g + 0.0514 * b;
+            let m = 0.2119 * r + 0.6807 * g + 0.1074 * b;
+            let s = 0.0883 * r + 0.2817 * g + 0.6300 * b;
+
+            let l_ = cbrtf(l);
+            let m_ = cbrtf(m);
+            let s_ = cbrtf(s);
+
+            let ok_l = 0.2105 * l_ + 0.7936 * m_ - 0.0041 * s_;
+            let mut ok_a = 1.9780 * l_ - 2.4286 * m_ + 0.4506 * s_;
+            let mut ok_b = 0.0259 * l_ + 0.7828 * m_ - 0.8087 * s_;
+
+            ok_a *= saturation;
+            ok_b *= saturation;
+
+            // Standard Oklab -> LMS' -> linear sRGB inverse.
+            let l2_ = ok_l + 0.3963377774 * ok_a + 0.2158037573 * ok_b;
+            let m2_ = ok_l - 0.1055613458 * ok_a - 0.0638541728 * ok_b;
+            let s2_ = ok_l - 0.0894841775 * ok_a - 1.2914855480 * ok_b;
+
+            let l2 = l2_ * l2_ * l2_;
+            let m2 = m2_ * m2_ * m2_;
+            let s2 = s2_ * s2_ * s2_;
+
+            let mut lin_r = 4.0767416621 * l2 - 3.3077115913 * m2 + 0.2309699292 * s2;
+            let mut lin_g = -1.2684380046 * l2 + 2.6097574011 * m2 - 0.3413193965 * s2;
+            let mut lin_b = -0.0041960863 * l2 - 0.7034186147 * m2 + 1.7076147010 * s2;
+
+            if lin_r < 0.0 { lin_r = 0.0; }
+            if lin_r > 1.0 { lin_r = 1.0; }
+            if lin_g < 0.0 { lin_g = 0.0; }
+            if lin_g > 1.0 { lin_g = 1.0; }
+            if lin_b < 0.0 { lin_b = 0.0; }
+            if lin_b > 1.0 { lin_b = 1.0; }
+
+            BUFFER[pi] = srgb_encode(lin_r);
+            BUFFER[pi + 1] = srgb_encode(lin_g);
+            BUFFER[pi + 2] = srgb_encode(lin_b);
+
+            if write_brightness {
+                FLOAT_BUFFER[i] = ok_l;
+            }
+        }
+    }
+}
+
+/// Round to the nearest integer. core has no f32::round (that needs libm,
+/// which #![no_std] doesn't have), so round via truncating cast instead.
+#[inline]
+fn roundf(x: f32) -> f32 {
+    if x >= 0.0 { (x + 0.5) as i32 as f32 } else { (x - 0.5) as i32 as f32 }
+}
+
+/// Apply Floyd-Steinberg error-diffusion dithering to brightness values in
+/// FLOAT_BUFFER before glyph quantization.
+/// Each pixel is snapped to the nearest of `chars_len` representable levels
+/// and the quantization error is spread to not-yet-visited neighbours using
+/// the standard kernel (right 7/16, bottom-left 3/16, bottom 5/16,
+/// bottom-right 1/16). Scan direction alternates per row (serpentine) to
+/// avoid directional artifacts.
+#[no_mangle]
+pub extern "C" fn apply_floyd_steinberg(width: usize, height: usize, chars_len: usize, invert: bool) {
+    if chars_len <= 1 {
+        return;
+    }
+    let levels = (chars_len - 1) as f32;
+    unsafe {
+        for y in 0..height {
+            let left_to_right = y % 2 == 0;
+            for i in 0..width {
+                let x = if left_to_right { i } else { width - 1 - i };
+                let idx = y * width + x;
+
+                let mut b = FLOAT_BUFFER[idx];
+                if invert {
+                    b = 1.0 - b;
+                }
+
+                let q = roundf(b * levels) / levels;
+                let err = b - q;
+
+                FLOAT_BUFFER[idx] = if invert { 1.0 - q } else { q };
+
+                let forward = if left_to_right { 1isize } else { -1isize };
+                diffuse_error(width, height, x, y, forward, err, invert);
+            }
+        }
+    }
+}
+
+/// Spread Floyd-Steinberg quantization error to the not-yet-processed
+/// neighbours of `(x, y)`, clamping accumulated brightness to 0..1.
+/// `forward` is +1 for left-to-right rows and -1 for right-to-left rows so
+/// the "ahead" and "behind" neighbours stay correct under serpentine scans.
+#[inline]
+unsafe fn diffuse_error(width: usize, height: usize, x: usize, y: usize, forward: isize, err: f32, invert: bool) {
+    let x = x as isize;
+    let y = y as isize;
+    let w = width as isize;
+    let h = height as isize;
+
+    let spread = |dx: isize, dy: isize, weight: f32| {
+        let nx = x + dx * forward;
+        let ny = y + dy;
+        if nx >= 0 && nx < w && ny >= 0 && ny < h {
+            let idx = (ny as usize) * width + (nx as usize);
+            let mut v = FLOAT_BUFFER[idx];
+            if invert {
+                v = 1.0 - v;
+            }
+            v += err * weight;
+            if v < 0.0 { v = 0.0; }
+            if v > 1.0 { v = 1.0; }
+            FLOAT_BUFFER[idx] = if invert { 1.0 - v } else { v };
+        }
+    };
+
+    spread(1, 0, 7.0 / 16.0);
+    spread(-1, 1, 3.0 / 16.0);
+    spread(0, 1, 5.0 / 16.0);
+    spread(1, 1, 1.0 / 16.0);
+}
+
+// Largest matrix_size apply_ordered_dither supports; the threshold table is
+// kept on the stack sized to this bound rather than allocated.
+const MAX_BAYER_SIZE: usize = 8;
+
+/// Apply ordered (Bayer) dithering to brightness values in FLOAT_BUFFER
+/// before glyph quantization. A zero-centered, normalized Bayer threshold
+/// map is added to each pixel's brightness, scaled by one quantization step
+/// (`1/(chars_len-1)`), then clamped to 0..1. `matrix_size` must be 2, 4 or
+/// 8; anything else is treated as 4.
+#[no_mangle]
+pub extern "C" fn apply_ordered_dither(width: usize, height: usize, chars_len: usize, matrix_size: usize) {
+    if chars_len <= 1 {
+        return;
+    }
+    let n = match matrix_size {
+        2 | 4 | 8 => matrix_size,
+        _ => 4,
+    };
+    let step = 1.0 / (chars_len - 1) as f32;
+    let area = (n * n) as f32;
+
+    let mut thresholds = [0.0f32; MAX_BAYER_SIZE * MAX_BAYER_SIZE];
+    for y in 0..n {
+        for x in 0..n {
+            let value = bayer_value(y, x, n) as f32;
+            thresholds[y * n + x] = (value + 0.5) / area - 0.5;
+        }
+    }
+
+    unsafe {
+        for y in 0..height {
+            for x in 0..width {
+                let idx = y * width + x;
+                let mut b = FLOAT_BUFFER[idx] + thresholds[(y % n) * n + x % n] * step;
+                if b < 0.0 { b = 0.0; }
+                if b > 1.0 { b = 1.0; }
+                FLOAT_BUFFER[idx] = b;
+            }
+        }
+    }
+}
+
+/// Recursively compute the value at `(row, col)` of the `n`x`n` Bayer matrix
+/// built by doubling `M_1 = [[0]]` via
+/// `M_2n = [[4*Mn, 4*Mn+2], [4*Mn+3, 4*Mn+1]]`.
+fn bayer_value(row: usize, col: usize, n: usize) -> u32 {
+    if n == 1 {
+        return 0;
+    }
+    let half = n / 2;
+    let sub = bayer_value(row % half, col % half, half);
+    let offset = match (row >= half, col >= half) {
+        (false, false) => 0,
+        (false, true) => 2,
+        (true, false) => 3,
+        (true, true) => 1,
+    };
+    4 * sub + offset
+}
+
 /// Generate ASCII string from brightness values
 /// Input: brightness in FLOAT_BUFFER, chars in BUFFER starting at chars_offset
 /// Output: ASCII string in BUFFER starting at output_offset